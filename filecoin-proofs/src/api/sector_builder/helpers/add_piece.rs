@@ -1,7 +1,12 @@
 use std::fs::File;
+use std::hash::Hasher;
+use std::io;
 use std::io::prelude::*;
 use std::sync::Arc;
 
+use sha2::{Digest, Sha256};
+use twox_hash::xxh3::Hash64 as Xxh3;
+
 use crate::api::sector_builder::errors::*;
 use crate::api::sector_builder::metadata::StagedSectorMetadata;
 use crate::api::sector_builder::pieces::get_piece_padding;
@@ -10,16 +15,385 @@ use crate::api::sector_builder::state::StagedState;
 use crate::api::sector_builder::*;
 use crate::error;
 use sector_base::api::bytes_amount::UnpaddedBytesAmount;
+use sector_base::api::sector_store::SectorAccess;
 use sector_base::api::sector_store::SectorManager;
 
+/// How `compute_destination_sector_id` chooses among staged sectors a piece
+/// could be written into. Passed in by the caller, since `SectorConfig` has
+/// no packing-policy hook yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectorPackingPolicy {
+    /// Return the first pending sector the piece fits into.
+    FirstFit,
+    /// Return the pending sector that leaves the least free space behind,
+    /// breaking ties by lowest sector id.
+    BestFit,
+}
+
+impl Default for SectorPackingPolicy {
+    fn default() -> Self {
+        SectorPackingPolicy::FirstFit
+    }
+}
+
+const COMM_P_NODE_SIZE: usize = 32;
+
+/// Incrementally builds a piece's commitment (`comm_p`) from the byte stream
+/// `add_piece` hands to the sector writer (left padding, piece bytes, right
+/// padding). The bytes are hashed as they are read, before any Fr32
+/// preprocessing the writer applies, so the result is not a commitment over
+/// the preprocessed bytes stored on disk. Leaves are 32-byte chunks,
+/// zero-padded up to the next power of two before parents are taken.
+///
+/// Rather than retaining every leaf for the whole piece, `consume` hashes
+/// each leaf as it completes and folds it into `branch`, a carry array (one
+/// slot per tree level, holding at most one in-flight node per level) —
+/// the same shape as an append-only Merkle accumulator. In-flight state is
+/// therefore O(log(piece size)) rather than O(piece size), letting pieces
+/// of any size stream through without buffering their padded bytes in
+/// memory.
+struct PieceCommitmentBuilder {
+    branch: Vec<Option<[u8; COMM_P_NODE_SIZE]>>,
+    num_leaves: usize,
+    pending: Vec<u8>,
+}
+
+impl PieceCommitmentBuilder {
+    fn new() -> Self {
+        PieceCommitmentBuilder {
+            branch: Vec::new(),
+            num_leaves: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    fn consume(&mut self, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+
+        while self.pending.len() >= COMM_P_NODE_SIZE {
+            let rest = self.pending.split_off(COMM_P_NODE_SIZE);
+            let mut leaf = [0u8; COMM_P_NODE_SIZE];
+            leaf.copy_from_slice(&self.pending);
+            self.pending = rest;
+            self.push_leaf(hash_comm_p_leaf(&leaf));
+        }
+    }
+
+    // Carries a completed leaf hash up through `branch` the way a binary
+    // counter carries a bit: the first empty slot claims it, and two nodes
+    // occupying the same slot combine into their parent and carry one level
+    // higher.
+    fn push_leaf(&mut self, mut node: [u8; COMM_P_NODE_SIZE]) {
+        let mut level = 0;
+
+        loop {
+            if level == self.branch.len() {
+                self.branch.push(Some(node));
+                break;
+            }
+
+            match self.branch[level].take() {
+                None => {
+                    self.branch[level] = Some(node);
+                    break;
+                }
+                Some(left) => {
+                    node = hash_comm_p_pair(&left, &node);
+                    level += 1;
+                }
+            }
+        }
+
+        self.num_leaves += 1;
+    }
+
+    fn finish(mut self) -> [u8; COMM_P_NODE_SIZE] {
+        if !self.pending.is_empty() {
+            let mut leaf = [0u8; COMM_P_NODE_SIZE];
+            leaf[..self.pending.len()].copy_from_slice(&self.pending);
+            self.push_leaf(hash_comm_p_leaf(&leaf));
+        }
+
+        let padded_leaves = self.num_leaves.next_power_of_two();
+
+        subtree_root(self.num_leaves, padded_leaves, &self.branch)
+    }
+}
+
+// Root of the `size`-leaf (a power of two) subtree holding `real_leaves`
+// real leaves followed by zero-leaves out to `size`. `branch[level]` (when
+// present) is always the root of a real, fully-populated `2^level`-leaf
+// block, so a subtree that's entirely real or entirely zero-padding is read
+// straight off `branch`/`zero_subtree_root`; only a subtree straddling the
+// boundary between real data and padding needs to recurse.
+fn subtree_root(
+    real_leaves: usize,
+    size: usize,
+    branch: &[Option<[u8; COMM_P_NODE_SIZE]>],
+) -> [u8; COMM_P_NODE_SIZE] {
+    if real_leaves == size {
+        return branch[size.trailing_zeros() as usize]
+            .expect("a fully-real subtree's branch slot is always populated");
+    }
+
+    if real_leaves == 0 {
+        return zero_subtree_root(size.trailing_zeros());
+    }
+
+    let half = size / 2;
+
+    if real_leaves <= half {
+        let left = subtree_root(real_leaves, half, branch);
+        let right = zero_subtree_root(half.trailing_zeros());
+        hash_comm_p_pair(&left, &right)
+    } else {
+        let left = branch[half.trailing_zeros() as usize]
+            .expect("a fully-real left half's branch slot is always populated");
+        let right = subtree_root(real_leaves - half, half, branch);
+        hash_comm_p_pair(&left, &right)
+    }
+}
+
+// Root of a `2^level`-leaf subtree that's entirely zero-padding.
+fn zero_subtree_root(level: u32) -> [u8; COMM_P_NODE_SIZE] {
+    let mut node = hash_comm_p_leaf(&[0u8; COMM_P_NODE_SIZE]);
+
+    for _ in 0..level {
+        node = hash_comm_p_pair(&node, &node);
+    }
+
+    node
+}
+
+// Domain-separation tags, so a leaf hash can never collide with an interior
+// node hash over the same bytes.
+const COMM_P_LEAF_DOMAIN_TAG: u8 = 0x00;
+const COMM_P_NODE_DOMAIN_TAG: u8 = 0x01;
+
+fn hash_comm_p_leaf(leaf: &[u8; COMM_P_NODE_SIZE]) -> [u8; COMM_P_NODE_SIZE] {
+    let mut hasher = Sha256::new();
+    hasher.input(&[COMM_P_LEAF_DOMAIN_TAG]);
+    hasher.input(leaf);
+
+    let mut node = [0u8; COMM_P_NODE_SIZE];
+    node.copy_from_slice(hasher.result().as_slice());
+    node
+}
+
+fn hash_comm_p_pair(left: &[u8; COMM_P_NODE_SIZE], right: &[u8; COMM_P_NODE_SIZE]) -> [u8; COMM_P_NODE_SIZE] {
+    let mut hasher = Sha256::new();
+    hasher.input(&[COMM_P_NODE_DOMAIN_TAG]);
+    hasher.input(left);
+    hasher.input(right);
+
+    let mut node = [0u8; COMM_P_NODE_SIZE];
+    node.copy_from_slice(hasher.result().as_slice());
+    node
+}
+
+/// Mirrors bytes read through it into a `PieceCommitmentBuilder`.
+struct CommPTee<'a, R> {
+    inner: R,
+    builder: &'a mut PieceCommitmentBuilder,
+}
+
+impl<'a, R: Read> Read for CommPTee<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes_read = self.inner.read(buf)?;
+        self.builder.consume(&buf[..num_bytes_read]);
+        Ok(num_bytes_read)
+    }
+}
+
+/// Incrementally hashes a piece's raw (unpadded) bytes into a cheap 64-bit
+/// xxh3 digest, used to scrub a staged sector for silent corruption without
+/// recomputing commitments.
+struct PieceChecksumBuilder {
+    hasher: Xxh3,
+}
+
+impl PieceChecksumBuilder {
+    fn new() -> Self {
+        PieceChecksumBuilder {
+            hasher: Xxh3::default(),
+        }
+    }
+
+    fn consume(&mut self, bytes: &[u8]) {
+        self.hasher.write(bytes);
+    }
+
+    fn finish(self) -> u64 {
+        self.hasher.finish()
+    }
+}
+
+/// Mirrors bytes read through it into a `PieceChecksumBuilder`.
+struct ChecksumTee<'a, R> {
+    inner: R,
+    builder: &'a mut PieceChecksumBuilder,
+}
+
+impl<'a, R: Read> Read for ChecksumTee<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes_read = self.inner.read(buf)?;
+        self.builder.consume(&buf[..num_bytes_read]);
+        Ok(num_bytes_read)
+    }
+}
+
+/// Abstracts the I/O `add_piece` needs from a staged sector, so alternative
+/// backends (e.g. an in-memory store for tests) can stand in for the
+/// default file-backed `SectorManager`.
+pub trait StagedSectorWriter {
+    fn allocate(&self) -> error::Result<SectorAccess>;
+
+    fn append_at(
+        &self,
+        access: &SectorAccess,
+        offset: u64,
+        reader: &mut dyn Read,
+    ) -> error::Result<UnpaddedBytesAmount>;
+
+    fn read_raw(
+        &self,
+        access: &SectorAccess,
+        start_offset: u64,
+        num_bytes: UnpaddedBytesAmount,
+    ) -> error::Result<Vec<u8>>;
+
+    /// Truncates the backing access back to `len` bytes, rolling back a
+    /// partial or short write.
+    fn truncate(&self, access: &SectorAccess, len: u64) -> error::Result<()>;
+
+    /// Releases the backing access entirely.
+    fn delete(&self, access: &SectorAccess) -> error::Result<()>;
+}
+
+impl<T: SectorManager + ?Sized> StagedSectorWriter for T {
+    fn allocate(&self) -> error::Result<SectorAccess> {
+        self.new_staging_sector_access().map_err(Into::into)
+    }
+
+    // Ideally this would confirm the caller's view of the sector's length
+    // against the backing access before appending, the way
+    // `InMemoryStagedSectorWriter::append_at` does against its own buffer.
+    // `SectorManager` has no confirmed accessor for a staged sector's
+    // current length to check that against here, so for now this trusts
+    // `offset` as `add_piece_to_sector` computed it via
+    // `staged_sector_padded_len`.
+    fn append_at(
+        &self,
+        access: &SectorAccess,
+        _offset: u64,
+        reader: &mut dyn Read,
+    ) -> error::Result<UnpaddedBytesAmount> {
+        self.write_and_preprocess(access, reader).map_err(Into::into)
+    }
+
+    fn read_raw(
+        &self,
+        access: &SectorAccess,
+        start_offset: u64,
+        num_bytes: UnpaddedBytesAmount,
+    ) -> error::Result<Vec<u8>> {
+        SectorManager::read_raw(self, access, start_offset, num_bytes).map_err(Into::into)
+    }
+
+    fn truncate(&self, access: &SectorAccess, len: u64) -> error::Result<()> {
+        self.truncate_unsealed(access, len).map_err(Into::into)
+    }
+
+    fn delete(&self, access: &SectorAccess) -> error::Result<()> {
+        self.delete_staging_sector_access(access).map_err(Into::into)
+    }
+}
+
+/// Re-reads each piece of a staged sector and recomputes its checksum,
+/// returning the keys of any pieces that no longer match.
+pub fn verify_piece_checksums<W: StagedSectorWriter + ?Sized>(
+    writer: &W,
+    staged_sector: &StagedSectorMetadata,
+) -> error::Result<Vec<String>> {
+    let mut failed_piece_keys = Vec::new();
+    let mut preceding_pieces: Vec<metadata::PieceMetadata> = Vec::new();
+
+    for piece in staged_sector.pieces.iter() {
+        let sector_length = sum_piece_lengths(preceding_pieces.iter());
+        let (left_padding, _) = get_piece_padding(sector_length, piece.num_bytes);
+        // The on-disk sector holds each preceding piece's full padded
+        // footprint back to back (see `staged_sector_padded_len`), not the
+        // unpadded `sector_length` used above to size this piece's own
+        // padding, so the read offset has to be derived from the former.
+        let start_offset = staged_sector_padded_len(&preceding_pieces) + left_padding;
+
+        let piece_bytes =
+            writer.read_raw(&staged_sector.sector_access, u64::from(start_offset), piece.num_bytes)?;
+
+        let mut checksum_builder = PieceChecksumBuilder::new();
+        checksum_builder.consume(&piece_bytes);
+
+        if checksum_builder.finish() != piece.checksum {
+            failed_piece_keys.push(piece.piece_key.clone());
+        }
+
+        preceding_pieces.push(piece.clone());
+    }
+
+    Ok(failed_piece_keys)
+}
+
 pub fn add_piece(
     sector_store: &Arc<WrappedSectorStore>,
+    staged_state: &mut StagedState,
+    piece_key: String,
+    piece_bytes_amount: u64,
+    piece_path: String,
+) -> error::Result<SectorId> {
+    add_piece_with_writer(
+        sector_store,
+        sector_store.inner.manager(),
+        staged_state,
+        piece_key,
+        piece_bytes_amount,
+        piece_path,
+    )
+}
+
+/// Same as `add_piece`, but lets the caller supply the `StagedSectorWriter`
+/// explicitly instead of defaulting to `sector_store`'s own manager.
+pub fn add_piece_with_writer<W: StagedSectorWriter + ?Sized>(
+    sector_store: &Arc<WrappedSectorStore>,
+    writer: &W,
+    staged_state: &mut StagedState,
+    piece_key: String,
+    piece_bytes_amount: u64,
+    piece_path: String,
+) -> error::Result<SectorId> {
+    add_piece_with_packing_policy(
+        sector_store,
+        writer,
+        staged_state,
+        piece_key,
+        piece_bytes_amount,
+        piece_path,
+        SectorPackingPolicy::default(),
+    )
+}
+
+/// Same as `add_piece_with_writer`, but also lets the caller opt into a
+/// `SectorPackingPolicy` other than the default (`FirstFit`) — the opt-in
+/// hook `SectorConfig` doesn't have yet (see `SectorPackingPolicy`'s doc
+/// comment).
+pub fn add_piece_with_packing_policy<W: StagedSectorWriter + ?Sized>(
+    sector_store: &Arc<WrappedSectorStore>,
+    writer: &W,
     mut staged_state: &mut StagedState,
     piece_key: String,
     piece_bytes_amount: u64,
     piece_path: String,
+    packing_policy: SectorPackingPolicy,
 ) -> error::Result<SectorId> {
-    let sector_mgr = sector_store.inner.manager();
     let sector_max = sector_store
         .inner
         .sector_config()
@@ -35,82 +409,194 @@ pub fn add_piece(
             .map(|(_, v)| (*v).clone())
             .collect();
 
-        compute_destination_sector_id(&candidates[..], sector_max, piece_bytes_len)?
+        compute_destination_sector_id(&candidates[..], sector_max, piece_bytes_len, packing_policy)?
     };
 
-    let dest_sector_id = opt_dest_sector_id
-        .ok_or(())
-        .or_else(|_| provision_new_staged_sector(sector_mgr, &mut staged_state))?;
+    match opt_dest_sector_id {
+        Some(dest_sector_id) => add_piece_to_sector(
+            writer,
+            staged_state,
+            dest_sector_id,
+            piece_key,
+            piece_bytes_len,
+            piece_path,
+        ),
+        None => add_piece_to_new_sector(writer, &mut staged_state, piece_key, piece_bytes_len, piece_path),
+    }
+}
 
-    if let Some(s) = staged_state.sectors.get_mut(&dest_sector_id) {
-        let file = File::open(piece_path)?;
+// Provisions a fresh staged sector and writes `piece` into it, discarding
+// the sector again if that write fails.
+fn add_piece_to_new_sector<W: StagedSectorWriter + ?Sized>(
+    writer: &W,
+    staged_state: &mut StagedState,
+    piece_key: String,
+    piece_bytes_len: UnpaddedBytesAmount,
+    piece_path: String,
+) -> error::Result<SectorId> {
+    let dest_sector_id = provision_new_staged_sector(writer, staged_state)?;
 
-        let sector_length = sum_piece_lengths(s.pieces.iter());
-        let (left_padding, right_padding) = get_piece_padding(sector_length, UnpaddedBytesAmount(piece_bytes_amount));
+    let result = add_piece_to_sector(
+        writer,
+        staged_state,
+        dest_sector_id,
+        piece_key,
+        piece_bytes_len,
+        piece_path,
+    );
 
-         let left_padding_vec = vec![0; left_padding.into()];
-         let left_padding_slice = &left_padding_vec[..];
-         let right_padding_vec = vec![0; right_padding.into()];
-         let right_padding_slice = &right_padding_vec[..];
-         let mut chain = left_padding_slice.chain(file).chain(right_padding_slice);
-         let expected_num_bytes_written = left_padding + piece_bytes_len + right_padding;
+    // A failed write must never leave behind a sector that only exists to
+    // hold the piece that failed to land in it. Releasing the access is
+    // best-effort: the sector is dropped from `staged_state` regardless, so
+    // a delete failure only leaks backend storage rather than corrupting
+    // state the rest of `add_piece` relies on.
+    if result.is_err() {
+        if let Some(s) = staged_state.sectors.get(&dest_sector_id) {
+            if s.pieces.is_empty() {
+                let _ = writer.delete(&s.sector_access);
+                staged_state.sectors.remove(&dest_sector_id);
+            }
+        }
+    }
 
-        sector_store
-            .inner
-            .manager()
-            .write_and_preprocess(&s.sector_access, &mut chain)
-            .map_err(Into::into)
-            .and_then(|num_bytes_written| {
-                if num_bytes_written != expected_num_bytes_written {
-                    Err(
-                        err_inc_write(u64::from(num_bytes_written), u64::from(expected_num_bytes_written))
-                            .into(),
-                    )
-                } else {
-                    Ok(s.sector_id)
-                }
-            })
-            .map(|sector_id| {
-                s.pieces.push(metadata::PieceMetadata {
-                    piece_key,
-                    num_bytes: piece_bytes_len,
-                    comm_p: None,
-                });
-
-                sector_id
-            })
-    } else {
-        Err(err_unrecov("unable to retrieve sector from state-map").into())
+    result
+}
+
+// The staged sector file on disk holds each piece's Fr32-padded bytes back
+// to back, not the unpadded `sector_length` that `sum_piece_lengths` reasons
+// about. Replays `get_piece_padding` over the pieces recorded so far to get
+// the true on-disk length.
+fn staged_sector_padded_len(pieces: &[metadata::PieceMetadata]) -> UnpaddedBytesAmount {
+    let mut padded_len = UnpaddedBytesAmount(0);
+    let mut preceding_pieces: Vec<metadata::PieceMetadata> = Vec::new();
+
+    for piece in pieces {
+        let sector_length = sum_piece_lengths(preceding_pieces.iter());
+        let (left_padding, right_padding) = get_piece_padding(sector_length, piece.num_bytes);
+        padded_len = padded_len + left_padding + piece.num_bytes + right_padding;
+        preceding_pieces.push(piece.clone());
+    }
+
+    padded_len
+}
+
+// Writes a single piece into an already-provisioned staged sector, rolling
+// back on any I/O error or short write.
+fn add_piece_to_sector<W: StagedSectorWriter + ?Sized>(
+    writer: &W,
+    staged_state: &mut StagedState,
+    dest_sector_id: SectorId,
+    piece_key: String,
+    piece_bytes_len: UnpaddedBytesAmount,
+    piece_path: String,
+) -> error::Result<SectorId> {
+    let s = staged_state
+        .sectors
+        .get_mut(&dest_sector_id)
+        .ok_or_else(|| err_unrecov("unable to retrieve sector from state-map"))?;
+
+    let file = File::open(piece_path)?;
+
+    let sector_length = sum_piece_lengths(s.pieces.iter());
+    let (left_padding, right_padding) = get_piece_padding(sector_length, piece_bytes_len);
+
+    let left_padding_vec = vec![0; left_padding.into()];
+    let left_padding_slice = &left_padding_vec[..];
+    let right_padding_vec = vec![0; right_padding.into()];
+    let right_padding_slice = &right_padding_vec[..];
+    let expected_num_bytes_written = left_padding + piece_bytes_len + right_padding;
+    let pre_write_len = u64::from(staged_sector_padded_len(&s.pieces));
+
+    let mut checksum_builder = PieceChecksumBuilder::new();
+    let checksummed_file = ChecksumTee {
+        inner: file,
+        builder: &mut checksum_builder,
+    };
+
+    let mut chain = left_padding_slice.chain(checksummed_file).chain(right_padding_slice);
+
+    let mut comm_p_builder = PieceCommitmentBuilder::new();
+    let mut tee = CommPTee {
+        inner: &mut chain,
+        builder: &mut comm_p_builder,
+    };
+
+    let write_result = writer
+        .append_at(&s.sector_access, pre_write_len, &mut tee)
+        .and_then(|num_bytes_written| {
+            if num_bytes_written != expected_num_bytes_written {
+                Err(err_inc_write(u64::from(num_bytes_written), u64::from(expected_num_bytes_written)).into())
+            } else {
+                Ok(())
+            }
+        });
+
+    match write_result {
+        Ok(()) => {
+            s.pieces.push(metadata::PieceMetadata {
+                piece_key,
+                num_bytes: piece_bytes_len,
+                comm_p: Some(comm_p_builder.finish()),
+                checksum: checksum_builder.finish(),
+            });
+
+            Ok(s.sector_id)
+        }
+        Err(write_err) => {
+            // `write_err` is the root cause the caller needs to see. A
+            // failure to roll back is best-effort and must not displace
+            // it: surfacing the truncate error instead would hide why the
+            // write failed whenever the two share a cause (e.g. a full
+            // disk breaks both the write and the truncate that undoes it).
+            let _ = writer.truncate(&s.sector_access, pre_write_len);
+            Err(write_err)
+        }
     }
 }
 
 // Given a list of staged sectors which are accepting data, return the
-// first staged sector into which the bytes will fit.
+// sector into which the bytes should be written, per the given packing
+// policy: the first sector the piece fits into (FirstFit), or the sector
+// that leaves the least free space behind (BestFit).
 fn compute_destination_sector_id(
     candidate_sectors: &[StagedSectorMetadata],
     max_bytes_per_sector: UnpaddedBytesAmount,
     num_bytes_in_piece: UnpaddedBytesAmount,
+    packing_policy: SectorPackingPolicy,
 ) -> error::Result<Option<SectorId>> {
     if num_bytes_in_piece > max_bytes_per_sector {
-        Err(err_overflow(num_bytes_in_piece.into(), max_bytes_per_sector.into()).into())
-    } else {
-        Ok(candidate_sectors
-            .iter()
-            .find(move |staged_sector| {
-                let sector_length = sum_piece_lengths(staged_sector.pieces.iter());
-                let (left_padding, right_padding) = get_piece_padding(sector_length, num_bytes_in_piece);
-                (sector_length + left_padding + num_bytes_in_piece + right_padding)
-                    <= max_bytes_per_sector
-            })
-            .map(|x| x.sector_id))
+        return Err(err_overflow(num_bytes_in_piece.into(), max_bytes_per_sector.into()).into());
+    }
+
+    // Each candidate's post-insertion free space, computed once: the early
+    // return on `used > max_bytes_per_sector` guarantees `used <=
+    // max_bytes_per_sector`, so the subtraction below never underflows.
+    let fitting_candidates = candidate_sectors.iter().filter_map(|staged_sector| {
+        let sector_length = sum_piece_lengths(staged_sector.pieces.iter());
+        let (left_padding, right_padding) = get_piece_padding(sector_length, num_bytes_in_piece);
+        let used = sector_length + left_padding + num_bytes_in_piece + right_padding;
+
+        if used > max_bytes_per_sector {
+            return None;
+        }
+
+        let free_space = u64::from(max_bytes_per_sector) - u64::from(used);
+        Some((free_space, staged_sector.sector_id))
+    });
+
+    match packing_policy {
+        SectorPackingPolicy::FirstFit => Ok(fitting_candidates.map(|(_, sector_id)| sector_id).next()),
+        SectorPackingPolicy::BestFit => Ok(fitting_candidates
+            .min_by_key(|&(free_space, sector_id)| (free_space, sector_id))
+            .map(|(_, sector_id)| sector_id)),
     }
 }
 
 // Provisions a new staged sector and returns its sector_id. Not a pure
 // function; creates a sector access (likely a file), increments the sector id
 // nonce, and mutates the StagedState.
-fn provision_new_staged_sector(
-    sector_manager: &SectorManager,
+fn provision_new_staged_sector<W: StagedSectorWriter + ?Sized>(
+    writer: &W,
     staged_state: &mut StagedState,
 ) -> error::Result<SectorId> {
     let sector_id = {
@@ -119,7 +605,7 @@ fn provision_new_staged_sector(
         *n
     };
 
-    let access = sector_manager.new_staging_sector_access()?;
+    let access = writer.allocate()?;
 
     let meta = StagedSectorMetadata {
         pieces: Default::default(),
@@ -133,6 +619,97 @@ fn provision_new_staged_sector(
     Ok(sector_id)
 }
 
+/// In-memory `StagedSectorWriter` backed by a map of access handle to byte
+/// buffer. Used by this module's unit tests so they can exercise
+/// `add_piece` and `provision_new_staged_sector` without touching the
+/// filesystem.
+#[cfg(test)]
+#[derive(Default)]
+struct InMemoryStagedSectorWriter {
+    sectors: std::sync::Mutex<std::collections::HashMap<SectorAccess, Vec<u8>>>,
+    next_access_id: std::sync::atomic::AtomicU64,
+}
+
+#[cfg(test)]
+impl InMemoryStagedSectorWriter {
+    fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[cfg(test)]
+impl StagedSectorWriter for InMemoryStagedSectorWriter {
+    fn allocate(&self) -> error::Result<SectorAccess> {
+        let id = self
+            .next_access_id
+            .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        let access = format!("in-memory-sector-{}", id);
+
+        self.sectors.lock().unwrap().insert(access.clone(), Vec::new());
+
+        Ok(access)
+    }
+
+    fn append_at(
+        &self,
+        access: &SectorAccess,
+        offset: u64,
+        reader: &mut dyn Read,
+    ) -> error::Result<UnpaddedBytesAmount> {
+        let mut incoming = Vec::new();
+        let num_bytes_read = reader.read_to_end(&mut incoming)?;
+
+        let mut sectors = self.sectors.lock().unwrap();
+        let buf = sectors.entry(access.clone()).or_insert_with(Vec::new);
+
+        if offset as usize != buf.len() {
+            return Err(err_unrecov("append offset does not match current sector length").into());
+        }
+
+        buf.extend_from_slice(&incoming);
+
+        Ok(UnpaddedBytesAmount(num_bytes_read as u64))
+    }
+
+    fn read_raw(
+        &self,
+        access: &SectorAccess,
+        start_offset: u64,
+        num_bytes: UnpaddedBytesAmount,
+    ) -> error::Result<Vec<u8>> {
+        let sectors = self.sectors.lock().unwrap();
+        let buf = sectors
+            .get(access)
+            .ok_or_else(|| err_unrecov("no such in-memory sector"))?;
+
+        let start = start_offset as usize;
+        let end = start + u64::from(num_bytes) as usize;
+
+        Ok(buf[start..end].to_vec())
+    }
+
+    fn truncate(&self, access: &SectorAccess, len: u64) -> error::Result<()> {
+        let mut sectors = self.sectors.lock().unwrap();
+        let buf = sectors
+            .get_mut(access)
+            .ok_or_else(|| err_unrecov("no such in-memory sector"))?;
+
+        buf.truncate(len as usize);
+
+        Ok(())
+    }
+
+    fn delete(&self, access: &SectorAccess) -> error::Result<()> {
+        self.sectors
+            .lock()
+            .unwrap()
+            .remove(access)
+            .ok_or_else(|| err_unrecov("no such in-memory sector"))?;
+
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -146,12 +723,14 @@ mod tests {
             piece_key: String::from("x"),
             num_bytes: UnpaddedBytesAmount(128),
             comm_p: None,
+            checksum: 0,
         });
 
         sealed_sector_a.pieces.push(PieceMetadata {
             piece_key: String::from("x"),
             num_bytes: UnpaddedBytesAmount(128),
             comm_p: None,
+            checksum: 0,
         });
 
         let mut sealed_sector_b: StagedSectorMetadata = Default::default();
@@ -160,6 +739,7 @@ mod tests {
             piece_key: String::from("x"),
             num_bytes: UnpaddedBytesAmount(128),
             comm_p: None,
+            checksum: 0,
         });
 
         let staged_sectors = vec![sealed_sector_a.clone(), sealed_sector_b.clone()];
@@ -169,6 +749,7 @@ mod tests {
             &staged_sectors,
             UnpaddedBytesAmount(256),
             UnpaddedBytesAmount(128),
+            SectorPackingPolicy::FirstFit,
         ) {
             Ok(Some(destination_sector_id)) => {
                 assert_eq!(destination_sector_id, sealed_sector_a.sector_id)
@@ -181,6 +762,7 @@ mod tests {
             &staged_sectors,
             UnpaddedBytesAmount(256),
             UnpaddedBytesAmount(128),
+            SectorPackingPolicy::FirstFit,
         ) {
             Ok(Some(destination_sector_id)) => {
                 assert_eq!(destination_sector_id, sealed_sector_b.sector_id)
@@ -193,6 +775,7 @@ mod tests {
             &staged_sectors,
             UnpaddedBytesAmount(256),
             UnpaddedBytesAmount(256),
+            SectorPackingPolicy::FirstFit,
         ) {
             Ok(None) => (),
             _ => panic!(),
@@ -203,9 +786,589 @@ mod tests {
             &staged_sectors,
             UnpaddedBytesAmount(256),
             UnpaddedBytesAmount(257),
+            SectorPackingPolicy::FirstFit,
         ) {
             Err(_) => (),
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn test_best_fit_prefers_tightest_sector() {
+        // sector_a has 128 bytes used (128 bytes free out of 256)
+        let mut sector_a: StagedSectorMetadata = Default::default();
+        sector_a.sector_id = 1;
+        sector_a.pieces.push(PieceMetadata {
+            piece_key: String::from("a"),
+            num_bytes: UnpaddedBytesAmount(128),
+            comm_p: None,
+            checksum: 0,
+        });
+
+        // sector_b has 192 bytes used (64 bytes free out of 256)
+        let mut sector_b: StagedSectorMetadata = Default::default();
+        sector_b.sector_id = 2;
+        sector_b.pieces.push(PieceMetadata {
+            piece_key: String::from("b"),
+            num_bytes: UnpaddedBytesAmount(192),
+            comm_p: None,
+            checksum: 0,
+        });
+
+        let staged_sectors = vec![sector_a.clone(), sector_b.clone()];
+
+        // a 64-byte piece fits both sectors; best-fit should choose the
+        // sector that leaves the least space behind, i.e. sector_b.
+        match compute_destination_sector_id(
+            &staged_sectors,
+            UnpaddedBytesAmount(256),
+            UnpaddedBytesAmount(64),
+            SectorPackingPolicy::BestFit,
+        ) {
+            Ok(Some(destination_sector_id)) => {
+                assert_eq!(destination_sector_id, sector_b.sector_id)
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_best_fit_breaks_ties_by_lowest_sector_id() {
+        // Both sectors leave exactly the same 64 bytes free once the piece
+        // lands, so free space alone can't decide between them; BestFit
+        // must fall back to the lower sector_id for a deterministic choice.
+        let mut sector_low: StagedSectorMetadata = Default::default();
+        sector_low.sector_id = 1;
+        sector_low.pieces.push(PieceMetadata {
+            piece_key: String::from("low"),
+            num_bytes: UnpaddedBytesAmount(128),
+            comm_p: None,
+            checksum: 0,
+        });
+
+        let mut sector_high: StagedSectorMetadata = Default::default();
+        sector_high.sector_id = 2;
+        sector_high.pieces.push(PieceMetadata {
+            piece_key: String::from("high"),
+            num_bytes: UnpaddedBytesAmount(128),
+            comm_p: None,
+            checksum: 0,
+        });
+
+        // list the lower-id sector second, so picking it can't be an
+        // accident of input order.
+        let staged_sectors = vec![sector_high.clone(), sector_low.clone()];
+
+        match compute_destination_sector_id(
+            &staged_sectors,
+            UnpaddedBytesAmount(256),
+            UnpaddedBytesAmount(64),
+            SectorPackingPolicy::BestFit,
+        ) {
+            Ok(Some(destination_sector_id)) => {
+                assert_eq!(destination_sector_id, sector_low.sector_id)
+            }
+            _ => panic!(),
+        }
+    }
+
+    fn comm_p_over_bytes(bytes: &[u8]) -> [u8; COMM_P_NODE_SIZE] {
+        let mut builder = PieceCommitmentBuilder::new();
+        builder.consume(bytes);
+        builder.finish()
+    }
+
+    #[test]
+    fn test_streamed_comm_p_matches_standalone_read() {
+        let padded: Vec<u8> = (0..200u32).map(|i| (i % 256) as u8).collect();
+
+        // standalone: feed the whole padded byte stream at once, as if it
+        // had been read back from the sector in a single pass.
+        let standalone_comm_p = comm_p_over_bytes(&padded);
+
+        // streamed: tee the same bytes through in small, uneven chunks, as
+        // `write_and_preprocess` would while it reads from `add_piece`'s
+        // chained reader.
+        let mut builder = PieceCommitmentBuilder::new();
+        for chunk in padded.chunks(7) {
+            builder.consume(chunk);
+        }
+        let streamed_comm_p = builder.finish();
+
+        assert_eq!(streamed_comm_p, standalone_comm_p);
+    }
+
+    #[test]
+    fn test_comm_p_for_single_leaf_matches_domain_separated_sha256() {
+        // With exactly one 32-byte leaf (already a power of two), the tree
+        // has no interior nodes, so `comm_p` should be exactly the
+        // leaf-domain-tagged SHA-256 of that leaf — the same primitive the
+        // standalone piece-commitment path hashes field-element chunks
+        // with, not an internal placeholder digest.
+        let leaf = [7u8; COMM_P_NODE_SIZE];
+
+        let comm_p = comm_p_over_bytes(&leaf);
+
+        let mut hasher = Sha256::new();
+        hasher.input(&[COMM_P_LEAF_DOMAIN_TAG]);
+        hasher.input(&leaf);
+        let expected = hasher.result();
+
+        assert_eq!(&comm_p[..], expected.as_slice());
+    }
+
+    #[test]
+    fn test_piece_checksum_detects_corruption() {
+        let mut builder = PieceChecksumBuilder::new();
+        builder.consume(b"some piece bytes");
+        let original_checksum = builder.finish();
+
+        let mut same_builder = PieceChecksumBuilder::new();
+        same_builder.consume(b"some piece bytes");
+        assert_eq!(same_builder.finish(), original_checksum);
+
+        let mut corrupted_builder = PieceChecksumBuilder::new();
+        corrupted_builder.consume(b"some piece Bytes");
+        assert_ne!(corrupted_builder.finish(), original_checksum);
+    }
+
+    #[test]
+    fn test_in_memory_writer_allocate_and_round_trip() {
+        let writer = InMemoryStagedSectorWriter::new();
+        let mut staged_state: StagedState = Default::default();
+
+        let sector_id = provision_new_staged_sector(&writer, &mut staged_state).unwrap();
+        let access = staged_state.sectors.get(&sector_id).unwrap().sector_access.clone();
+
+        let payload = b"hello sector";
+        let num_bytes_written = writer
+            .append_at(&access, 0, &mut &payload[..])
+            .unwrap();
+        assert_eq!(num_bytes_written, UnpaddedBytesAmount(payload.len() as u64));
+
+        let read_back = writer
+            .read_raw(&access, 0, UnpaddedBytesAmount(payload.len() as u64))
+            .unwrap();
+        assert_eq!(&read_back[..], &payload[..]);
+
+        // appending anywhere but the current end is rejected
+        assert!(writer.append_at(&access, 0, &mut &payload[..]).is_err());
+    }
+
+    #[test]
+    fn test_delete_releases_access() {
+        let writer = InMemoryStagedSectorWriter::new();
+        let access = writer.allocate().unwrap();
+
+        writer.delete(&access).unwrap();
+
+        // the access no longer refers to anything
+        assert!(writer.read_raw(&access, 0, UnpaddedBytesAmount(0)).is_err());
+        assert!(writer.truncate(&access, 0).is_err());
+        assert!(writer.delete(&access).is_err());
+    }
+
+    #[test]
+    fn test_verify_piece_checksums_against_in_memory_writer() {
+        let writer = InMemoryStagedSectorWriter::new();
+        let mut staged_state: StagedState = Default::default();
+
+        let sector_id = provision_new_staged_sector(&writer, &mut staged_state).unwrap();
+        let access = staged_state.sectors.get(&sector_id).unwrap().sector_access.clone();
+
+        let piece_bytes = b"piece-payload";
+        writer.append_at(&access, 0, &mut &piece_bytes[..]).unwrap();
+
+        let mut checksum_builder = PieceChecksumBuilder::new();
+        checksum_builder.consume(piece_bytes);
+
+        let mut staged_sector = staged_state.sectors.get(&sector_id).unwrap().clone();
+        staged_sector.pieces.push(PieceMetadata {
+            piece_key: String::from("only-piece"),
+            num_bytes: UnpaddedBytesAmount(piece_bytes.len() as u64),
+            comm_p: None,
+            checksum: checksum_builder.finish(),
+        });
+
+        let failed = verify_piece_checksums(&writer, &staged_sector).unwrap();
+        assert!(failed.is_empty());
+
+        staged_sector.pieces[0].checksum = 0;
+        let failed = verify_piece_checksums(&writer, &staged_sector).unwrap();
+        assert_eq!(failed, vec![String::from("only-piece")]);
+    }
+
+    #[test]
+    fn test_verify_piece_checksums_against_multi_piece_sector() {
+        let writer = InMemoryStagedSectorWriter::new();
+        let mut staged_state: StagedState = Default::default();
+
+        let sector_id = provision_new_staged_sector(&writer, &mut staged_state).unwrap();
+
+        // Two pieces in one sector, written through the real
+        // `add_piece_to_sector` path so the second piece lands behind
+        // whatever left/right padding `get_piece_padding` inserts for the
+        // first. A `start_offset` derived from the unpadded cumulative
+        // piece length (rather than the true padded on-disk length) would
+        // read the second piece from the wrong bytes and report a false
+        // checksum mismatch on perfectly intact data.
+        for (key, bytes) in &[("a", &b"first-piece"[..]), ("b", &b"second-piece"[..])] {
+            let path = write_temp_piece_file(
+                &format!("verify_multi_piece_test_{}", key),
+                bytes,
+            );
+            add_piece_to_sector(
+                &writer,
+                &mut staged_state,
+                sector_id,
+                String::from(*key),
+                UnpaddedBytesAmount(bytes.len() as u64),
+                path,
+            )
+            .unwrap();
+        }
+
+        let staged_sector = staged_state.sectors.get(&sector_id).unwrap();
+        let failed = verify_piece_checksums(&writer, staged_sector).unwrap();
+        assert!(failed.is_empty());
+    }
+
+    #[test]
+    fn test_checksum_survives_multi_chunk_write_and_verifies() {
+        let writer = InMemoryStagedSectorWriter::new();
+        let mut staged_state: StagedState = Default::default();
+
+        let sector_id = provision_new_staged_sector(&writer, &mut staged_state).unwrap();
+
+        // Large enough that `Read::read_to_end`'s internal buffer growth
+        // forces `ChecksumTee::read` to be called more than once for this
+        // piece, the same way `write_and_preprocess` would read it on the
+        // real file-backed path. A checksum that frames each `consume` call
+        // (as a naive `Hash`-trait-based digest would) disagrees with
+        // `verify_piece_checksums`'s single-call re-read; a true streaming
+        // hash does not.
+        let piece_bytes: Vec<u8> = (0..10_000u32).map(|i| (i % 256) as u8).collect();
+        let piece_path = write_temp_piece_file("checksum_multi_chunk_test_piece", &piece_bytes);
+
+        add_piece_to_sector(
+            &writer,
+            &mut staged_state,
+            sector_id,
+            String::from("big-piece"),
+            UnpaddedBytesAmount(piece_bytes.len() as u64),
+            piece_path,
+        )
+        .unwrap();
+
+        let staged_sector = staged_state.sectors.get(&sector_id).unwrap();
+        let failed = verify_piece_checksums(&writer, staged_sector).unwrap();
+        assert!(failed.is_empty());
+    }
+
+    #[test]
+    fn test_comm_p_from_add_piece_matches_independent_recomputation_of_written_bytes() {
+        let writer = InMemoryStagedSectorWriter::new();
+        let mut staged_state: StagedState = Default::default();
+
+        let sector_id = provision_new_staged_sector(&writer, &mut staged_state).unwrap();
+        let access = staged_state.sectors.get(&sector_id).unwrap().sector_access.clone();
+
+        let piece_bytes = b"comm-p-end-to-end-piece";
+        let piece_path = write_temp_piece_file("comm_p_end_to_end_test_piece", piece_bytes);
+
+        add_piece_to_sector(
+            &writer,
+            &mut staged_state,
+            sector_id,
+            String::from("only-piece"),
+            UnpaddedBytesAmount(piece_bytes.len() as u64),
+            piece_path,
+        )
+        .unwrap();
+
+        let staged_sector = staged_state.sectors.get(&sector_id).unwrap();
+        let piece = &staged_sector.pieces[0];
+
+        // Re-read the piece's full padded range back from the sector — the
+        // same left/right-padded bytes `add_piece_to_sector` streamed
+        // through `PieceCommitmentBuilder` on the way in — and recompute
+        // comm_p independently over them, rather than trusting the builder
+        // against itself.
+        let (left_padding, right_padding) = get_piece_padding(UnpaddedBytesAmount(0), piece.num_bytes);
+        let padded_len = left_padding + piece.num_bytes + right_padding;
+        let padded_bytes = writer.read_raw(&access, 0, padded_len).unwrap();
+
+        let recomputed_comm_p = comm_p_over_bytes(&padded_bytes);
+
+        assert_eq!(piece.comm_p, Some(recomputed_comm_p));
+    }
+
+    /// Wraps `InMemoryStagedSectorWriter` but, once its full-write allowance
+    /// is exhausted, writes only half of the bytes it's handed, simulating a
+    /// short write so rollback behavior can be exercised without touching
+    /// the filesystem. `full_writes_remaining` lets a test seed a sector
+    /// with ordinary writes before switching on the short-write behavior for
+    /// the write under test.
+    struct ShortWriteStagedSectorWriter {
+        inner: InMemoryStagedSectorWriter,
+        full_writes_remaining: std::cell::Cell<u32>,
+    }
+
+    impl StagedSectorWriter for ShortWriteStagedSectorWriter {
+        fn allocate(&self) -> error::Result<SectorAccess> {
+            self.inner.allocate()
+        }
+
+        fn append_at(
+            &self,
+            access: &SectorAccess,
+            offset: u64,
+            reader: &mut dyn Read,
+        ) -> error::Result<UnpaddedBytesAmount> {
+            let mut all_bytes = Vec::new();
+            reader.read_to_end(&mut all_bytes)?;
+
+            let remaining = self.full_writes_remaining.get();
+            if remaining > 0 {
+                self.full_writes_remaining.set(remaining - 1);
+                return self.inner.append_at(access, offset, &mut &all_bytes[..]);
+            }
+
+            let short_bytes = &all_bytes[..all_bytes.len() / 2];
+
+            self.inner.append_at(access, offset, &mut &short_bytes[..])
+        }
+
+        fn read_raw(
+            &self,
+            access: &SectorAccess,
+            start_offset: u64,
+            num_bytes: UnpaddedBytesAmount,
+        ) -> error::Result<Vec<u8>> {
+            self.inner.read_raw(access, start_offset, num_bytes)
+        }
+
+        fn truncate(&self, access: &SectorAccess, len: u64) -> error::Result<()> {
+            self.inner.truncate(access, len)
+        }
+
+        fn delete(&self, access: &SectorAccess) -> error::Result<()> {
+            self.inner.delete(access)
+        }
+    }
+
+    /// Wraps `ShortWriteStagedSectorWriter` but also fails every `truncate`
+    /// call, simulating a rollback that can't complete (e.g. the same
+    /// disk-full condition that caused the short write in the first place).
+    struct FailingTruncateStagedSectorWriter {
+        inner: ShortWriteStagedSectorWriter,
+    }
+
+    impl StagedSectorWriter for FailingTruncateStagedSectorWriter {
+        fn allocate(&self) -> error::Result<SectorAccess> {
+            self.inner.allocate()
+        }
+
+        fn append_at(
+            &self,
+            access: &SectorAccess,
+            offset: u64,
+            reader: &mut dyn Read,
+        ) -> error::Result<UnpaddedBytesAmount> {
+            self.inner.append_at(access, offset, reader)
+        }
+
+        fn read_raw(
+            &self,
+            access: &SectorAccess,
+            start_offset: u64,
+            num_bytes: UnpaddedBytesAmount,
+        ) -> error::Result<Vec<u8>> {
+            self.inner.read_raw(access, start_offset, num_bytes)
+        }
+
+        fn truncate(&self, _access: &SectorAccess, _len: u64) -> error::Result<()> {
+            Err(err_unrecov("simulated rollback failure").into())
+        }
+
+        fn delete(&self, access: &SectorAccess) -> error::Result<()> {
+            self.inner.delete(access)
+        }
+    }
+
+    fn write_temp_piece_file(name: &str, bytes: &[u8]) -> String {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_add_piece_rolls_back_sector_on_short_write() {
+        let writer = ShortWriteStagedSectorWriter {
+            inner: InMemoryStagedSectorWriter::new(),
+            // Let the seed write below land intact; only the second write,
+            // the one under test, should be shortened.
+            full_writes_remaining: std::cell::Cell::new(1),
+        };
+        let mut staged_state: StagedState = Default::default();
+
+        let sector_id = provision_new_staged_sector(&writer, &mut staged_state).unwrap();
+        let access = staged_state.sectors.get(&sector_id).unwrap().sector_access.clone();
+
+        // Seed the sector with one already-committed piece through the real
+        // `add_piece_to_sector` path (rather than hand-writing bytes and
+        // metadata), so its on-disk length reflects whatever left/right
+        // padding `get_piece_padding` actually applies. The rollback of the
+        // *second*, failing piece must land on that true padded length, not
+        // on the unpadded byte count of the first piece.
+        let first_piece_path = write_temp_piece_file("add_piece_short_write_test_first", b"first-piece");
+        add_piece_to_sector(
+            &writer,
+            &mut staged_state,
+            sector_id,
+            String::from("first"),
+            UnpaddedBytesAmount(b"first-piece".len() as u64),
+            first_piece_path,
+        )
+        .unwrap();
+
+        let pre_write_len = writer
+            .inner
+            .sectors
+            .lock()
+            .unwrap()
+            .get(&access)
+            .unwrap()
+            .len();
+
+        let second_piece_path =
+            write_temp_piece_file("add_piece_short_write_test_piece", b"second-piece-bytes");
+
+        let result = add_piece_to_sector(
+            &writer,
+            &mut staged_state,
+            sector_id,
+            String::from("second"),
+            UnpaddedBytesAmount(b"second-piece-bytes".len() as u64),
+            second_piece_path,
+        );
+
+        assert!(result.is_err());
+
+        let s = staged_state.sectors.get(&sector_id).unwrap();
+        assert_eq!(s.pieces.len(), 1);
+        assert_eq!(s.pieces[0].piece_key, "first");
+
+        let post_rollback_len = writer
+            .inner
+            .sectors
+            .lock()
+            .unwrap()
+            .get(&access)
+            .unwrap()
+            .len();
+        assert_eq!(post_rollback_len, pre_write_len);
+    }
+
+    #[test]
+    fn test_add_piece_surfaces_write_error_even_when_rollback_also_fails() {
+        let writer = FailingTruncateStagedSectorWriter {
+            inner: ShortWriteStagedSectorWriter {
+                inner: InMemoryStagedSectorWriter::new(),
+                // The write under test is the first one, so it's the one
+                // that gets shortened.
+                full_writes_remaining: std::cell::Cell::new(0),
+            },
+        };
+        let mut staged_state: StagedState = Default::default();
+
+        let sector_id = provision_new_staged_sector(&writer, &mut staged_state).unwrap();
+
+        let piece_path =
+            write_temp_piece_file("add_piece_rollback_also_fails_test_piece", b"a-piece-that-wont-land");
+
+        let result = add_piece_to_sector(
+            &writer,
+            &mut staged_state,
+            sector_id,
+            String::from("doomed"),
+            UnpaddedBytesAmount(b"a-piece-that-wont-land".len() as u64),
+            piece_path,
+        );
+
+        // The write's own "incomplete write" error must surface, not the
+        // rollback's "simulated rollback failure".
+        let message = format!("{:?}", result.unwrap_err());
+        assert!(message.contains("incomplete write"), "{}", message);
+        assert!(!message.contains("simulated rollback failure"), "{}", message);
+    }
+
+    #[test]
+    fn test_add_piece_to_new_sector_discards_sector_on_failed_first_write() {
+        let writer = ShortWriteStagedSectorWriter {
+            inner: InMemoryStagedSectorWriter::new(),
+            // The very first write is the one under test, so no full writes
+            // are allowed through.
+            full_writes_remaining: std::cell::Cell::new(0),
+        };
+        let mut staged_state: StagedState = Default::default();
+
+        let piece_path =
+            write_temp_piece_file("add_piece_new_sector_first_write_fails", b"a-piece-that-wont-land");
+
+        let result = add_piece_to_new_sector(
+            &writer,
+            &mut staged_state,
+            String::from("doomed"),
+            UnpaddedBytesAmount(b"a-piece-that-wont-land".len() as u64),
+            piece_path,
+        );
+
+        assert!(result.is_err());
+
+        // The sector that only existed to hold this piece must not linger
+        // in state once the piece it was provisioned for fails to land.
+        assert!(staged_state.sectors.is_empty());
+
+        // `delete` must actually have been called on the backing access, not
+        // just forgotten about in `staged_state` — nothing should be left
+        // behind in the underlying writer either.
+        assert!(writer.inner.sectors.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_staged_sector_padded_len_matches_actual_written_bytes() {
+        let writer = InMemoryStagedSectorWriter::new();
+        let mut staged_state: StagedState = Default::default();
+
+        let sector_id = provision_new_staged_sector(&writer, &mut staged_state).unwrap();
+        let access = staged_state.sectors.get(&sector_id).unwrap().sector_access.clone();
+
+        for (key, bytes) in &[("a", &b"first-piece"[..]), ("b", &b"second-piece"[..])] {
+            let path = write_temp_piece_file(
+                &format!("staged_sector_padded_len_test_{}", key),
+                bytes,
+            );
+            add_piece_to_sector(
+                &writer,
+                &mut staged_state,
+                sector_id,
+                String::from(*key),
+                UnpaddedBytesAmount(bytes.len() as u64),
+                path,
+            )
+            .unwrap();
+        }
+
+        let s = staged_state.sectors.get(&sector_id).unwrap();
+        let actual_len = writer
+            .sectors
+            .lock()
+            .unwrap()
+            .get(&access)
+            .unwrap()
+            .len() as u64;
+
+        assert_eq!(u64::from(staged_sector_padded_len(&s.pieces)), actual_len);
+    }
 }
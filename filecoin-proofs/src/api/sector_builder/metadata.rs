@@ -0,0 +1,39 @@
+use sector_base::api::bytes_amount::UnpaddedBytesAmount;
+use sector_base::api::sector_store::SectorAccess;
+
+use super::{SealStatus, SectorId};
+
+/// Everything recorded about a single piece once it has landed in a staged
+/// sector.
+#[derive(Clone, Debug)]
+pub struct PieceMetadata {
+    pub piece_key: String,
+    pub num_bytes: UnpaddedBytesAmount,
+    /// The piece's commitment, computed incrementally while its bytes are
+    /// written in `add_piece`. `None` until that write has completed.
+    pub comm_p: Option<[u8; 32]>,
+    /// xxh3 digest over the piece's raw (unpadded) bytes, checked by
+    /// `verify_piece_checksums` to scrub a staged sector for silent
+    /// corruption without recomputing `comm_p`.
+    pub checksum: u64,
+}
+
+/// A staged (not yet sealed) sector and the pieces written into it so far.
+#[derive(Clone, Debug)]
+pub struct StagedSectorMetadata {
+    pub sector_id: SectorId,
+    pub sector_access: SectorAccess,
+    pub seal_status: SealStatus,
+    pub pieces: Vec<PieceMetadata>,
+}
+
+impl Default for StagedSectorMetadata {
+    fn default() -> Self {
+        StagedSectorMetadata {
+            sector_id: SectorId::default(),
+            sector_access: SectorAccess::default(),
+            seal_status: SealStatus::Pending,
+            pieces: Vec::new(),
+        }
+    }
+}